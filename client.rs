@@ -0,0 +1,100 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `Client`, the facade that picks a `Backend` (X11 XSETTINGS or the XDG
+//! Desktop Portal) so callers don't have to know which one is in play.
+
+use libc::c_int;
+use std::env;
+use std::sync::mpsc::Receiver;
+use x11_dl::xlib::{Display, XEvent};
+use {Backend, Error, FromSetting, PortalClient, Setting, SettingChange, X11Client};
+
+/// Which `Backend` a `Client` connected with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BackendKind {
+    X11,
+    Portal,
+}
+
+enum ClientBackend {
+    X11(X11Client),
+    Portal(PortalClient),
+}
+
+/// A settings client that dispatches to whichever `Backend` fits the
+/// running session.
+pub struct Client {
+    backend: ClientBackend,
+}
+
+impl Client {
+    /// Picks a backend automatically: the portal if `$WAYLAND_DISPLAY` is
+    /// set or its D-Bus interface is reachable, X11 otherwise.
+    ///
+    /// `unsafe` because, when the X11 backend is picked, `display` is
+    /// dereferenced; see `X11Client::new`.
+    pub unsafe fn auto(display: *mut Display, screen: c_int)
+                       -> Result<(Client, Receiver<SettingChange>), Error> {
+        let kind = if env::var_os("WAYLAND_DISPLAY").is_some() || PortalClient::is_available() {
+            BackendKind::Portal
+        } else {
+            BackendKind::X11
+        };
+        Client::with_backend(kind, display, screen)
+    }
+
+    /// Connects using an explicitly chosen backend, bypassing the
+    /// autodetection in `auto`.
+    ///
+    /// `unsafe` for the same reason as `auto`.
+    pub unsafe fn with_backend(kind: BackendKind, display: *mut Display, screen: c_int)
+                               -> Result<(Client, Receiver<SettingChange>), Error> {
+        match kind {
+            BackendKind::X11 => {
+                let (client, receiver) = X11Client::subscribe(display, screen);
+                Ok((Client { backend: ClientBackend::X11(client) }, receiver))
+            }
+            BackendKind::Portal => {
+                let (client, receiver) = PortalClient::subscribe()?;
+                Ok((Client { backend: ClientBackend::Portal(client) }, receiver))
+            }
+        }
+    }
+
+    pub fn get_setting(&self, name: &[u8]) -> Result<Setting, Error> {
+        match self.backend {
+            ClientBackend::X11(ref client) => client.get_setting(name),
+            ClientBackend::Portal(ref client) => client.get_setting(name),
+        }
+    }
+
+    /// Reads and converts a setting in one step; see `X11Client::get`.
+    pub fn get<T: FromSetting>(&self, name: &[u8]) -> Result<T, Error> {
+        let setting = self.get_setting(name)?;
+        T::from_setting(setting.data())
+    }
+
+    pub fn enumerate(&self) -> Result<Vec<Vec<u8>>, Error> {
+        match self.backend {
+            ClientBackend::X11(ref client) => Backend::enumerate(client),
+            ClientBackend::Portal(ref client) => Backend::enumerate(client),
+        }
+    }
+
+    /// Forwards an `XEvent` to the X11 backend; a no-op under the portal,
+    /// which is driven entirely by D-Bus signals instead.
+    pub fn process_event(&mut self, event: &XEvent) -> bool {
+        match self.backend {
+            ClientBackend::X11(ref mut client) => client.process_event(event),
+            ClientBackend::Portal(_) => false,
+        }
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Typed conversions out of `SettingData`, used by `Client::get`.
+
+use {Error, SettingData, XSettingsResult};
+
+/// A value that can be produced from a `SettingData`, used by `Client::get`
+/// to avoid making every caller match on the enum by hand.
+pub trait FromSetting: Sized {
+    fn from_setting(data: SettingData) -> Result<Self, Error>;
+}
+
+impl FromSetting for i32 {
+    fn from_setting(data: SettingData) -> Result<i32, Error> {
+        match data {
+            SettingData::Int(value) => Ok(value),
+            _ => Err(XSettingsResult::TypeMismatch),
+        }
+    }
+}
+
+/// XSETTINGS has no boolean type; by convention booleans are stored as the
+/// integers 0 and 1.
+impl FromSetting for bool {
+    fn from_setting(data: SettingData) -> Result<bool, Error> {
+        match data {
+            SettingData::Int(value) => Ok(value != 0),
+            _ => Err(XSettingsResult::TypeMismatch),
+        }
+    }
+}
+
+impl FromSetting for String {
+    fn from_setting(data: SettingData) -> Result<String, Error> {
+        match data {
+            SettingData::String(bytes) => {
+                String::from_utf8(bytes.to_vec()).map_err(|_| XSettingsResult::Failed)
+            }
+            _ => Err(XSettingsResult::TypeMismatch),
+        }
+    }
+}
+
+impl FromSetting for Vec<u8> {
+    fn from_setting(data: SettingData) -> Result<Vec<u8>, Error> {
+        match data {
+            SettingData::String(bytes) => Ok(bytes.to_vec()),
+            _ => Err(XSettingsResult::TypeMismatch),
+        }
+    }
+}
+
+/// An RGBA color, the typed form of `SettingData::Color`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rgba {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub alpha: u16,
+}
+
+impl FromSetting for Rgba {
+    fn from_setting(data: SettingData) -> Result<Rgba, Error> {
+        match data {
+            SettingData::Color(color) => {
+                Ok(Rgba {
+                    red: color.red,
+                    green: color.green,
+                    blue: color.blue,
+                    alpha: color.alpha,
+                })
+            }
+            _ => Err(XSettingsResult::TypeMismatch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FromSetting, Rgba};
+    use {SettingData, XSettingsColor, XSettingsResult};
+
+    #[test]
+    fn i32_from_int() {
+        assert_eq!(i32::from_setting(SettingData::Int(42)), Ok(42));
+    }
+
+    #[test]
+    fn i32_from_non_int_is_type_mismatch() {
+        assert_eq!(i32::from_setting(SettingData::String(b"42")),
+                   Err(XSettingsResult::TypeMismatch));
+    }
+
+    #[test]
+    fn bool_from_int() {
+        assert_eq!(bool::from_setting(SettingData::Int(0)), Ok(false));
+        assert_eq!(bool::from_setting(SettingData::Int(1)), Ok(true));
+    }
+
+    #[test]
+    fn bool_from_non_int_is_type_mismatch() {
+        assert_eq!(bool::from_setting(SettingData::None),
+                   Err(XSettingsResult::TypeMismatch));
+    }
+
+    #[test]
+    fn string_from_string() {
+        assert_eq!(String::from_setting(SettingData::String(b"Adwaita")),
+                   Ok("Adwaita".to_string()));
+    }
+
+    #[test]
+    fn string_from_non_string_is_type_mismatch() {
+        assert_eq!(String::from_setting(SettingData::Int(1)),
+                   Err(XSettingsResult::TypeMismatch));
+    }
+
+    #[test]
+    fn vec_u8_from_string() {
+        assert_eq!(Vec::<u8>::from_setting(SettingData::String(b"Adwaita")),
+                   Ok(b"Adwaita".to_vec()));
+    }
+
+    #[test]
+    fn vec_u8_from_non_string_is_type_mismatch() {
+        assert_eq!(Vec::<u8>::from_setting(SettingData::Int(1)),
+                   Err(XSettingsResult::TypeMismatch));
+    }
+
+    #[test]
+    fn rgba_from_color() {
+        let color = XSettingsColor {
+            red: 0x1111,
+            green: 0x2222,
+            blue: 0x3333,
+            alpha: 0xffff,
+        };
+        assert_eq!(Rgba::from_setting(SettingData::Color(color)),
+                   Ok(Rgba { red: 0x1111, green: 0x2222, blue: 0x3333, alpha: 0xffff }));
+    }
+
+    #[test]
+    fn rgba_from_non_color_is_type_mismatch() {
+        assert_eq!(Rgba::from_setting(SettingData::Int(1)),
+                   Err(XSettingsResult::TypeMismatch));
+    }
+}
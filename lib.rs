@@ -8,34 +8,43 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+extern crate dbus;
 extern crate libc;
+#[cfg(feature = "futures")]
+extern crate futures;
 extern crate x11_dl;
 
-use libc::{c_char, c_int, c_long, c_ulong, c_ushort, c_void};
-use std::ffi::{CStr, CString};
+mod backend;
+mod client;
+mod convert;
+mod manager;
+mod portal;
+mod subscribe;
+mod wire;
+
+pub use backend::Backend;
+pub use client::{BackendKind, Client};
+pub use convert::{FromSetting, Rgba};
+pub use manager::Manager;
+pub use portal::PortalClient;
+#[cfg(feature = "futures")]
+pub use subscribe::SettingChangeStream;
+pub use subscribe::SettingChange;
+
+use libc::{c_int, c_long, c_ushort};
+use std::collections::HashMap;
+use std::ffi::CString;
 use std::fmt::{self, Debug, Formatter};
-use std::marker::PhantomData;
-use std::mem;
 use std::ptr;
-use x11_dl::xlib::{Bool, Display, False, Window, XEvent};
+use x11_dl::xlib::{self, Atom, Display, False, Window, XEvent, Xlib};
 
 pub use self::XSettingsResult as Error;
 
-pub type XSettingsNotifyFunc = unsafe extern "C" fn(name: *const c_char,
-                                                    action: XSettingsAction,
-                                                    setting: *mut XSettingsSetting,
-                                                    cb_data: *mut c_void);
-
-pub type XSettingsWatchFunc = unsafe extern "C" fn(window: Window,
-                                                   is_start: Bool,
-                                                   mask: c_long,
-                                                   cb_data: *mut c_void);
-
 pub type NotifyFunc = Box<for<'a> FnMut(&[u8], XSettingsAction, SettingRef<'a>)>;
 
 pub type WatchFunc = Box<FnMut(Window, bool, c_long)>;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(u32)]
 pub enum XSettingsAction {
     New = 0,
@@ -61,6 +70,8 @@ pub enum XSettingsResult {
     Failed = 3,
     NoEntry = 4,
     DuplicateEntry = 5,
+    TypeMismatch = 6,
+    Unsupported = 7,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -72,13 +83,32 @@ pub struct XSettingsColor {
     alpha: c_ushort,
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug)]
-pub struct XSettingsSetting {
-    name: *const c_char,
-    setting_type: XSettingsType,
-    data: u64,
-    last_change_serial: c_ulong,
+/// A setting value that owns its data, used internally to track what the
+/// manager last published so changes can be diffed and reported.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OwnedSettingData {
+    Int(i32),
+    String(Vec<u8>),
+    Color(XSettingsColor),
+    None,
+}
+
+impl OwnedSettingData {
+    fn as_ref(&self) -> SettingData {
+        match *self {
+            OwnedSettingData::Int(value) => SettingData::Int(value),
+            OwnedSettingData::String(ref value) => SettingData::String(value),
+            OwnedSettingData::Color(color) => SettingData::Color(color),
+            OwnedSettingData::None => SettingData::None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OwnedSetting {
+    name: Vec<u8>,
+    last_change_serial: u32,
+    data: OwnedSettingData,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -89,96 +119,58 @@ pub enum SettingData<'a> {
     None,
 }
 
-impl<'a> SettingData<'a> {
-    unsafe fn from_raw(setting: *mut XSettingsSetting) -> SettingData<'a> {
-        match (*setting).setting_type {
-            XSettingsType::Int => {
-                SettingData::Int(*mem::transmute::<_,*const c_int>(&(*setting).data))
-            }
-            XSettingsType::String => {
-                let string = CStr::from_ptr(mem::transmute::<_,*const c_char>((*setting).data));
-                SettingData::String(string.to_bytes())
-            }
-            XSettingsType::Color => {
-                SettingData::Color(*mem::transmute::<_,*const XSettingsColor>(&(*setting).data))
-            }
-            XSettingsType::None => SettingData::None,
-        }
-    }
-}
-
+/// An owned setting value, returned by `X11Client::get_setting`.
+#[derive(Clone)]
 pub struct Setting {
-    setting: *mut XSettingsSetting,
+    data: OwnedSettingData,
 }
 
 impl Debug for Setting {
-    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         self.data().fmt(f)
     }
 }
 
-impl Drop for Setting {
-    fn drop(&mut self) {
-        unsafe {
-            xsettings_setting_free(self.setting)
-        }
-    }
-}
-
-impl Clone for Setting {
-    fn clone(&self) -> Setting {
-        unsafe {
-            Setting::from_raw(xsettings_setting_copy(self.setting))
-        }
-    }
-}
-
 impl PartialEq for Setting {
     fn eq(&self, other: &Setting) -> bool {
-        unsafe {
-            xsettings_setting_equal(self.setting, other.setting) != 0
-        }
+        self.data == other.data
     }
 }
 
 impl Setting {
-    pub unsafe fn from_raw(setting: *mut XSettingsSetting) -> Setting {
+    fn from_owned(data: OwnedSettingData) -> Setting {
         Setting {
-            setting: setting,
+            data: data,
         }
     }
 
     pub fn data<'a>(&'a self) -> SettingData<'a> {
-        unsafe {
-            SettingData::from_raw(self.setting)
-        }
+        self.data.as_ref()
     }
 }
 
+/// A borrowed setting value, only valid for the duration of the notify
+/// callback that produced it.
 #[derive(Copy, Clone)]
 pub struct SettingRef<'a> {
-    setting: *mut XSettingsSetting,
-    phantom: PhantomData<&'a mut XSettingsSetting>,
+    data: &'a OwnedSettingData,
 }
 
 impl<'a> Debug for SettingRef<'a> {
-    fn fmt(&self, f: &mut Formatter) -> Result<(),fmt::Error> {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         self.data().fmt(f)
     }
 }
 
 impl<'a> SettingRef<'a> {
-    pub unsafe fn from_raw(setting: *mut XSettingsSetting) -> SettingRef<'a> {
+    fn from_owned(data: &'a OwnedSettingData) -> SettingRef<'a> {
         SettingRef {
-            setting: setting,
-            phantom: PhantomData,
+            data: data,
         }
     }
 
     pub fn data(&self) -> SettingData<'a> {
-        unsafe {
-            SettingData::from_raw(self.setting)
-        }
+        self.data.as_ref()
     }
 }
 
@@ -187,100 +179,332 @@ struct Callbacks {
     watch: WatchFunc,
 }
 
-unsafe extern "C" fn notify_func(name: *const c_char,
-                                 action: XSettingsAction,
-                                 setting: *mut XSettingsSetting,
-                                 cb_data: *mut c_void) {
-    let callbacks: *mut Callbacks = mem::transmute(cb_data);
-    let name = CStr::from_ptr(name);
-    ((*callbacks).notify)(name.to_bytes(), action, SettingRef::from_raw(setting))
+/// A connection to the XSETTINGS manager of a single screen.
+///
+/// Unlike the old C-backed client, this talks XSETTINGS directly: it locates
+/// the selection owner of `_XSETTINGS_S<screen>`, reads the
+/// `_XSETTINGS_SETTINGS` property off that window, and parses the blob
+/// itself, so the crate has no dependency on the `Xsettings-client` C
+/// library.
+pub struct X11Client {
+    xlib: Xlib,
+    display: *mut Display,
+    root: Window,
+    selection_atom: Atom,
+    settings_atom: Atom,
+    manager_atom: Atom,
+    manager_window: Option<Window>,
+    settings: HashMap<Vec<u8>, OwnedSetting>,
+    callbacks: Box<Callbacks>,
 }
 
-unsafe extern "C" fn watch_func(window: Window,
-                                is_start: Bool,
-                                mask: c_long,
-                                cb_data: *mut c_void) {
-    let callbacks: *mut Callbacks = mem::transmute(cb_data);
-    ((*callbacks).watch)(window, is_start != False, mask)
-}
+impl X11Client {
+    /// Connects to the XSETTINGS manager for `screen` on `display`.
+    ///
+    /// `notify` is invoked once per added, changed, or removed setting, and
+    /// `watch` is invoked whenever the client needs events selected (or
+    /// deselected) on a window; the caller is expected to forward the
+    /// relevant `XEvent`s for that window to `process_event`.
+    pub unsafe fn new(display: *mut Display, screen: c_int, notify: NotifyFunc, watch: WatchFunc)
+                      -> X11Client {
+        let xlib = Xlib::open().expect("xsettings: failed to open libX11");
+        let root = (xlib.XRootWindow)(display, screen);
+        let selection_atom = intern_atom(&xlib, display, &format!("_XSETTINGS_S{}", screen));
+        let settings_atom = intern_atom(&xlib, display, "_XSETTINGS_SETTINGS");
+        let manager_atom = intern_atom(&xlib, display, "MANAGER");
+
+        let mut client = X11Client {
+            xlib: xlib,
+            display: display,
+            root: root,
+            selection_atom: selection_atom,
+            settings_atom: settings_atom,
+            manager_atom: manager_atom,
+            manager_window: None,
+            settings: HashMap::new(),
+            callbacks: Box::new(Callbacks {
+                notify: notify,
+                watch: watch,
+            }),
+        };
+
+        client.call_watch(root, true, xlib::PropertyChangeMask | xlib::StructureNotifyMask);
+        client.refresh_manager();
+        client
+    }
 
-#[repr(C)]
-pub struct XSettingsClient {
-    _private: c_int,
-}
+    fn call_watch(&mut self, window: Window, is_start: bool, mask: c_long) {
+        (self.callbacks.watch)(window, is_start, mask)
+    }
 
-pub struct Client {
-    client: *mut XSettingsClient,
-    #[allow(dead_code)]
-    callbacks: Box<Callbacks>,
-}
+    fn call_notify(&mut self, name: &[u8], action: XSettingsAction, data: &OwnedSettingData) {
+        (self.callbacks.notify)(name, action, SettingRef::from_owned(data))
+    }
 
-impl Drop for Client {
-    fn drop(&mut self) {
-        unsafe {
-            xsettings_client_destroy(self.client)
+    /// Re-locates the `_XSETTINGS_S<screen>` selection owner, starting or
+    /// stopping the watch on the old and new manager windows as needed.
+    fn refresh_manager(&mut self) {
+        let owner = unsafe {
+            let owner = (self.xlib.XGetSelectionOwner)(self.display, self.selection_atom);
+            if owner == 0 { None } else { Some(owner) }
+        };
+
+        if owner == self.manager_window {
+            return;
+        }
+
+        if let Some(old) = self.manager_window {
+            self.call_watch(old, false, xlib::PropertyChangeMask | xlib::StructureNotifyMask);
+        }
+        self.manager_window = owner;
+
+        match owner {
+            Some(window) => {
+                self.call_watch(window, true, xlib::PropertyChangeMask |
+                                 xlib::StructureNotifyMask);
+                self.refresh_settings();
+            }
+            None => self.clear_settings(),
         }
     }
-}
 
-impl Client {
-    pub unsafe fn new(display: *mut Display, screen: c_int, notify: NotifyFunc, watch: WatchFunc)
-                      -> Client {
-        let mut callbacks = Box::new(Callbacks {
-            notify: notify,
-            watch: watch,
-        });
-        let client = xsettings_client_new(
-            display,
-            screen,
-            notify_func,
-            watch_func,
-            mem::transmute::<&mut Callbacks,*mut c_void>(&mut *callbacks));
-        Client {
-            client: client,
-            callbacks: callbacks,
+    /// Rereads the `_XSETTINGS_SETTINGS` property off the manager window and
+    /// diffs it against what was last seen, firing notify callbacks for
+    /// anything that was added, changed, or removed.
+    fn refresh_settings(&mut self) {
+        let window = match self.manager_window {
+            Some(window) => window,
+            None => return,
+        };
+
+        let bytes = match unsafe { self.get_property(window) } {
+            Some(bytes) => bytes,
+            None => return,
+        };
+
+        let (_serial, parsed) = match wire::parse(&bytes) {
+            Ok(result) => result,
+            Err(_) => return,
+        };
+
+        for (name, action, data) in diff_settings(&mut self.settings, parsed) {
+            self.call_notify(&name, action, &data);
         }
     }
 
-    pub fn process_event(&mut self, event: &XEvent) -> bool {
-        unsafe {
-            xsettings_client_process_event(self.client, event) != False
+    /// Called when the manager goes away: every currently-known setting is
+    /// reported as deleted.
+    fn clear_settings(&mut self) {
+        let removed: Vec<(Vec<u8>, OwnedSettingData)> = self.settings.drain()
+            .map(|(name, setting)| (name, setting.data))
+            .collect();
+        for (name, data) in removed {
+            self.call_notify(&name, XSettingsAction::Deleted, &data);
         }
     }
 
-    pub fn get_setting(&self, name: &[u8]) -> Result<Setting,Error> {
-        let name = CString::new(name).expect("name() must be a valid C string!");
-        let mut setting = ptr::null_mut();
+    unsafe fn get_property(&self, window: Window) -> Option<Vec<u8>> {
+        let mut actual_type = 0;
+        let mut actual_format = 0;
+        let mut n_items = 0;
+        let mut bytes_after = 0;
+        let mut data = ptr::null_mut();
+
+        let status = (self.xlib.XGetWindowProperty)(
+            self.display,
+            window,
+            self.settings_atom,
+            0,
+            i32::max_value() as c_long,
+            False,
+            0,
+            &mut actual_type,
+            &mut actual_format,
+            &mut n_items,
+            &mut bytes_after,
+            &mut data);
+
+        if status != 0 || data.is_null() || actual_format != 8 {
+            if !data.is_null() {
+                (self.xlib.XFree)(data as *mut _);
+            }
+            return None;
+        }
+
+        let bytes = std::slice::from_raw_parts(data, n_items as usize).to_vec();
+        (self.xlib.XFree)(data as *mut _);
+        Some(bytes)
+    }
+
+    /// Processes an `XEvent`, returning `true` if it was relevant to this
+    /// client (a settings change, or a change of manager).
+    pub fn process_event(&mut self, event: &XEvent) -> bool {
         unsafe {
-            let result = xsettings_client_get_setting(self.client, name.as_ptr(), &mut setting);
-            if result == XSettingsResult::Success {
-                Ok(Setting::from_raw(setting))
-            } else {
-                Err(result)
+            match event.type_ {
+                xlib::SelectionClear => {
+                    if event.selection_clear.selection == self.selection_atom {
+                        self.refresh_manager();
+                        true
+                    } else {
+                        false
+                    }
+                }
+                xlib::ClientMessage => {
+                    if event.client_message.message_type == self.manager_atom &&
+                            event.client_message.data.get_long(1) as Atom ==
+                                self.selection_atom {
+                        self.refresh_manager();
+                        true
+                    } else {
+                        false
+                    }
+                }
+                xlib::DestroyNotify => {
+                    if Some(event.destroy_window.window) == self.manager_window {
+                        self.refresh_manager();
+                        true
+                    } else {
+                        false
+                    }
+                }
+                xlib::PropertyNotify => {
+                    if Some(event.property.window) == self.manager_window &&
+                            event.property.atom == self.settings_atom {
+                        self.refresh_settings();
+                        true
+                    } else {
+                        false
+                    }
+                }
+                _ => false,
             }
         }
     }
+
+    pub fn get_setting(&self, name: &[u8]) -> Result<Setting, Error> {
+        match self.settings.get(name) {
+            Some(setting) => Ok(Setting::from_owned(setting.data.clone())),
+            None => Err(XSettingsResult::NoEntry),
+        }
+    }
+
+    /// Reads and converts a setting in one step, e.g.
+    /// `client.get::<i32>(b"Xft/DPI")`, instead of matching on `SettingData`
+    /// by hand.
+    pub fn get<T: FromSetting>(&self, name: &[u8]) -> Result<T, Error> {
+        let setting = self.get_setting(name)?;
+        T::from_setting(setting.data())
+    }
+
+    /// Lists the names of every setting currently known to this client.
+    pub fn enumerate(&self) -> Vec<Vec<u8>> {
+        self.settings.keys().cloned().collect()
+    }
 }
 
-#[link(name = "Xsettings-client")]
-extern {
-    fn xsettings_setting_copy(setting: *mut XSettingsSetting) -> *mut XSettingsSetting;
-    fn xsettings_setting_free(setting: *mut XSettingsSetting);
-    fn xsettings_setting_equal(setting_a: *mut XSettingsSetting, setting_b: *mut XSettingsSetting)
-                               -> c_int;
-
-    fn xsettings_client_new(display: *mut Display,
-                            screen: c_int,
-                            notify: XSettingsNotifyFunc,
-                            watch: XSettingsWatchFunc,
-                            cb_data: *mut c_void)
-                            -> *mut XSettingsClient;
-    fn xsettings_client_destroy(client: *mut XSettingsClient);
-    fn xsettings_client_process_event(client: *mut XSettingsClient,
-                                      event: *const XEvent) -> Bool;
-    fn xsettings_client_get_setting(client: *mut XSettingsClient,
-                                    name: *const c_char,
-                                    setting: *mut *mut XSettingsSetting)
-                                    -> XSettingsResult;
+impl Backend for X11Client {
+    fn get_setting(&self, name: &[u8]) -> Result<Setting, Error> {
+        X11Client::get_setting(self, name)
+    }
+
+    fn enumerate(&self) -> Result<Vec<Vec<u8>>, Error> {
+        Ok(X11Client::enumerate(self))
+    }
 }
 
+unsafe fn intern_atom(xlib: &Xlib, display: *mut Display, name: &str) -> Atom {
+    let name = CString::new(name).expect("atom name must be a valid C string!");
+    (xlib.XInternAtom)(display, name.as_ptr(), False)
+}
+
+/// Diffs a freshly-parsed `_XSETTINGS_SETTINGS` blob against `previous`,
+/// updating `previous` in place to match and returning the New/Changed/
+/// Deleted notifications the diff implies. Pulled out of `refresh_settings`
+/// so the diffing logic can be unit-tested without a live X connection.
+fn diff_settings(previous: &mut HashMap<Vec<u8>, OwnedSetting>, parsed: Vec<OwnedSetting>)
+                 -> Vec<(Vec<u8>, XSettingsAction, OwnedSettingData)> {
+    let mut seen = HashMap::with_capacity(parsed.len());
+    let mut changes = Vec::new();
+
+    for setting in parsed {
+        let name = setting.name.clone();
+        let action = match previous.get(&name) {
+            None => Some(XSettingsAction::New),
+            Some(old) if old.last_change_serial != setting.last_change_serial =>
+                Some(XSettingsAction::Changed),
+            Some(_) => None,
+        };
+        if let Some(action) = action {
+            changes.push((name.clone(), action, setting.data.clone()));
+        }
+        seen.insert(name, setting);
+    }
+
+    let removed: Vec<Vec<u8>> = previous.keys()
+        .filter(|name| !seen.contains_key(*name))
+        .cloned()
+        .collect();
+    for name in removed {
+        let data = previous.remove(&name).unwrap().data;
+        changes.push((name, XSettingsAction::Deleted, data));
+    }
+
+    *previous = seen;
+    changes
+}
+
+#[cfg(test)]
+mod diff_settings_tests {
+    use super::{diff_settings, OwnedSetting, OwnedSettingData, XSettingsAction};
+    use std::collections::HashMap;
+
+    fn setting(name: &[u8], last_change_serial: u32, value: i32) -> OwnedSetting {
+        OwnedSetting {
+            name: name.to_vec(),
+            last_change_serial: last_change_serial,
+            data: OwnedSettingData::Int(value),
+        }
+    }
+
+    #[test]
+    fn reports_new_settings() {
+        let mut previous = HashMap::new();
+        let changes = diff_settings(&mut previous, vec![setting(b"Xft/DPI", 1, 96)]);
+        assert_eq!(changes, vec![
+            (b"Xft/DPI".to_vec(), XSettingsAction::New, OwnedSettingData::Int(96)),
+        ]);
+        assert_eq!(previous.get(&b"Xft/DPI".to_vec()).unwrap().data, OwnedSettingData::Int(96));
+    }
+
+    #[test]
+    fn reports_changed_settings_when_serial_advances() {
+        let mut previous = HashMap::new();
+        previous.insert(b"Xft/DPI".to_vec(), setting(b"Xft/DPI", 1, 96));
+
+        let changes = diff_settings(&mut previous, vec![setting(b"Xft/DPI", 2, 120)]);
+        assert_eq!(changes, vec![
+            (b"Xft/DPI".to_vec(), XSettingsAction::Changed, OwnedSettingData::Int(120)),
+        ]);
+    }
+
+    #[test]
+    fn reports_nothing_when_serial_is_unchanged() {
+        let mut previous = HashMap::new();
+        previous.insert(b"Xft/DPI".to_vec(), setting(b"Xft/DPI", 1, 96));
+
+        let changes = diff_settings(&mut previous, vec![setting(b"Xft/DPI", 1, 96)]);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn reports_deleted_settings_missing_from_the_new_blob() {
+        let mut previous = HashMap::new();
+        previous.insert(b"Xft/DPI".to_vec(), setting(b"Xft/DPI", 1, 96));
+
+        let changes = diff_settings(&mut previous, vec![]);
+        assert_eq!(changes, vec![
+            (b"Xft/DPI".to_vec(), XSettingsAction::Deleted, OwnedSettingData::Int(96)),
+        ]);
+        assert!(previous.is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `Manager`, for processes that want to *be* the XSETTINGS manager: a
+//! settings daemon, or a test harness driving a `Client` in the same
+//! process. It owns the `_XSETTINGS_S<screen>` selection and serializes its
+//! settings into `_XSETTINGS_SETTINGS` using the same wire format `wire`
+//! parses on the client side.
+
+use libc::c_int;
+use std::collections::HashMap;
+use std::mem;
+use std::ptr;
+use x11_dl::xlib::{self, Atom, ClientMessageData, Display, False, Window, XEvent, Xlib};
+use {intern_atom, wire, Error, OwnedSetting, OwnedSettingData, Rgba, XSettingsColor,
+     XSettingsResult};
+
+/// Owns the XSETTINGS selection for a screen and publishes settings to it.
+pub struct Manager {
+    xlib: Xlib,
+    display: *mut Display,
+    root: Window,
+    window: Window,
+    selection_atom: Atom,
+    settings_atom: Atom,
+    manager_atom: Atom,
+    owns_selection: bool,
+    serial: u32,
+    settings: HashMap<Vec<u8>, OwnedSetting>,
+}
+
+impl Manager {
+    /// Acquires the `_XSETTINGS_S<screen>` selection on `display` and
+    /// announces itself on the root window, returning `Error::Access` if
+    /// another manager wins the race for ownership.
+    pub unsafe fn new(display: *mut Display, screen: c_int) -> Result<Manager, Error> {
+        let xlib = Xlib::open().expect("xsettings: failed to open libX11");
+        let root = (xlib.XRootWindow)(display, screen);
+        let selection_atom = intern_atom(&xlib, display, &format!("_XSETTINGS_S{}", screen));
+        let settings_atom = intern_atom(&xlib, display, "_XSETTINGS_SETTINGS");
+        let manager_atom = intern_atom(&xlib, display, "MANAGER");
+
+        let window = (xlib.XCreateSimpleWindow)(display, root, 0, 0, 1, 1, 0, 0, 0);
+
+        // ICCCM warns against acquiring a selection with `CurrentTime`: two
+        // clients racing for the same selection can't be ordered without a
+        // real timestamp. Get one from the server the standard way, with a
+        // zero-length property change and the `PropertyNotify` it provokes.
+        let timestamp = server_timestamp(&xlib, display, window);
+        (xlib.XSetSelectionOwner)(display, selection_atom, window, timestamp);
+
+        if (xlib.XGetSelectionOwner)(display, selection_atom) != window {
+            (xlib.XDestroyWindow)(display, window);
+            return Err(XSettingsResult::Access);
+        }
+
+        announce(&xlib, display, root, manager_atom, selection_atom, window, timestamp);
+
+        Ok(Manager {
+            xlib: xlib,
+            display: display,
+            root: root,
+            window: window,
+            selection_atom: selection_atom,
+            settings_atom: settings_atom,
+            manager_atom: manager_atom,
+            owns_selection: true,
+            serial: 0,
+            settings: HashMap::new(),
+        })
+    }
+
+    pub fn set_int(&mut self, name: &[u8], value: i32) {
+        self.set(name, OwnedSettingData::Int(value));
+    }
+
+    pub fn set_string(&mut self, name: &[u8], value: &[u8]) {
+        self.set(name, OwnedSettingData::String(value.to_vec()));
+    }
+
+    pub fn set_color(&mut self, name: &[u8], color: Rgba) {
+        self.set(name, OwnedSettingData::Color(XSettingsColor {
+            red: color.red,
+            green: color.green,
+            blue: color.blue,
+            alpha: color.alpha,
+        }));
+    }
+
+    /// Removes a setting; clients will see it reported as deleted.
+    pub fn remove(&mut self, name: &[u8]) {
+        if self.settings.remove(name).is_some() {
+            self.serial += 1;
+            self.publish();
+        }
+    }
+
+    fn set(&mut self, name: &[u8], data: OwnedSettingData) {
+        self.serial += 1;
+        let serial = self.serial;
+        self.settings.insert(name.to_vec(), OwnedSetting {
+            name: name.to_vec(),
+            last_change_serial: serial,
+            data: data,
+        });
+        self.publish();
+    }
+
+    fn publish(&mut self) {
+        if !self.owns_selection {
+            return;
+        }
+        let settings: Vec<OwnedSetting> = self.settings.values().cloned().collect();
+        let bytes = wire::serialize(self.serial, &settings);
+        unsafe {
+            (self.xlib.XChangeProperty)(self.display, self.window, self.settings_atom,
+                                        self.settings_atom, 8, xlib::PropModeReplace,
+                                        bytes.as_ptr(), bytes.len() as c_int);
+            (self.xlib.XFlush)(self.display);
+        }
+    }
+
+    /// Processes an `XEvent`; watches for `SelectionClear` so a manager that
+    /// loses the selection (e.g. to a replacement) stops trying to publish.
+    pub fn process_event(&mut self, event: &XEvent) -> bool {
+        unsafe {
+            match event.type_ {
+                xlib::SelectionClear if event.selection_clear.selection == self.selection_atom => {
+                    self.owns_selection = false;
+                    true
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+impl Drop for Manager {
+    fn drop(&mut self) {
+        unsafe {
+            (self.xlib.XDestroyWindow)(self.display, self.window);
+        }
+    }
+}
+
+/// Obtains a current server timestamp by forcing a `PropertyNotify` on a
+/// scratch property of `window` and reading the time off it, per the
+/// standard ICCCM trick for selection-acquisition timestamps.
+unsafe fn server_timestamp(xlib: &Xlib, display: *mut Display, window: Window) -> xlib::Time {
+    let timestamp_atom = intern_atom(xlib, display, "_XSETTINGS_TIMESTAMP_PROP");
+    (xlib.XSelectInput)(display, window, xlib::PropertyChangeMask);
+    (xlib.XChangeProperty)(display, window, timestamp_atom, timestamp_atom, 8,
+                           xlib::PropModeAppend, ptr::null(), 0);
+
+    let mut event: XEvent = mem::zeroed();
+    loop {
+        (xlib.XWindowEvent)(display, window, xlib::PropertyChangeMask, &mut event);
+        if event.type_ == xlib::PropertyNotify && event.property.window == window &&
+                event.property.atom == timestamp_atom {
+            return event.property.time;
+        }
+    }
+}
+
+unsafe fn announce(xlib: &Xlib, display: *mut Display, root: Window, manager_atom: Atom,
+                   selection_atom: Atom, window: Window, timestamp: xlib::Time) {
+    let mut event: XEvent = mem::zeroed();
+    event.client_message.type_ = xlib::ClientMessage;
+    event.client_message.window = root;
+    event.client_message.message_type = manager_atom;
+    event.client_message.format = 32;
+    event.client_message.data = ClientMessageData::from([
+        timestamp as i64,
+        selection_atom as i64,
+        window as i64,
+        0,
+        0,
+    ]);
+    (xlib.XSendEvent)(display, root, False, xlib::StructureNotifyMask, &mut event);
+    (xlib.XFlush)(display);
+}
@@ -0,0 +1,205 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A `Backend` that reads settings from `org.freedesktop.portal.Settings`
+//! over D-Bus, so the crate works under Wayland and in sandboxed
+//! environments where no XSETTINGS manager is reachable.
+//!
+//! Setting names are namespaced `"<namespace>/<key>"` byte strings, e.g.
+//! `b"org.gnome.desktop.interface/clock-format"`; this module splits on the
+//! last `/` before asking the portal's `ReadOne` method for them. The older
+//! `Read` method is not called, so portals that only offer `Read` report
+//! every setting as `Error::NoEntry`.
+
+use dbus::arg::{RefArg, Variant};
+use dbus::blocking::Connection;
+use std::str;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use {Backend, Error, OwnedSettingData, Setting, SettingChange, XSettingsAction, XSettingsColor,
+     XSettingsResult};
+
+const DBUS_BUS_NAME: &'static str = "org.freedesktop.DBus";
+const DBUS_OBJECT_PATH: &'static str = "/org/freedesktop/DBus";
+
+const PORTAL_BUS_NAME: &'static str = "org.freedesktop.portal.Desktop";
+const PORTAL_OBJECT_PATH: &'static str = "/org/freedesktop/portal/desktop";
+const PORTAL_INTERFACE: &'static str = "org.freedesktop.portal.Settings";
+const PORTAL_TIMEOUT_MS: u64 = 5000;
+
+/// A connection to `org.freedesktop.portal.Settings`.
+pub struct PortalClient {
+    connection: Connection,
+    /// Created by `subscribe` and checked by its background thread; cleared
+    /// by `Drop` (or by the signal handler once the receiver is gone) so
+    /// that thread stops polling instead of running for the life of the
+    /// process. `None` for a
+    /// `PortalClient` built via `new`, which has no background thread.
+    running: Option<Arc<AtomicBool>>,
+}
+
+impl PortalClient {
+    pub fn new() -> Result<PortalClient, Error> {
+        let connection = Connection::new_session().map_err(|_| XSettingsResult::Failed)?;
+        Ok(PortalClient {
+            connection: connection,
+            running: None,
+        })
+    }
+
+    /// True if `org.freedesktop.portal.Desktop` is actually owned on the
+    /// session bus; used by `Client::auto` to decide whether to prefer this
+    /// backend. A reachable session bus on its own proves nothing, since one
+    /// is present on plain X11 sessions too.
+    pub fn is_available() -> bool {
+        let connection = match Connection::new_session() {
+            Ok(connection) => connection,
+            Err(_) => return false,
+        };
+        let proxy = connection.with_proxy(DBUS_BUS_NAME, DBUS_OBJECT_PATH,
+                                          Duration::from_millis(PORTAL_TIMEOUT_MS));
+        let result: Result<(bool,), _> =
+            proxy.method_call(DBUS_BUS_NAME, "NameHasOwner", (PORTAL_BUS_NAME,));
+        match result {
+            Ok((has_owner,)) => has_owner,
+            Err(_) => false,
+        }
+    }
+
+    /// Subscribes to `SettingChanged` and returns a client alongside the
+    /// channel those changes are delivered on.
+    pub fn subscribe() -> Result<(PortalClient, Receiver<SettingChange>), Error> {
+        let reader = Connection::new_session().map_err(|_| XSettingsResult::Failed)?;
+        let watcher = Connection::new_session().map_err(|_| XSettingsResult::Failed)?;
+        let (sender, receiver) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+
+        let signal_running = running.clone();
+        let proxy = watcher.with_proxy(PORTAL_BUS_NAME, PORTAL_OBJECT_PATH,
+                                       Duration::from_millis(PORTAL_TIMEOUT_MS));
+        proxy.match_signal(move |signal: SettingChanged, _: &Connection, _: &dbus::Message| {
+            let mut name = signal.namespace.into_bytes();
+            name.push(b'/');
+            name.extend_from_slice(signal.key.as_bytes());
+            if sender.send(SettingChange {
+                name: name,
+                action: XSettingsAction::Changed,
+                value: Some(variant_to_data(&signal.value)),
+            }).is_err() {
+                // The receiver is gone; stop polling instead of leaking
+                // this thread.
+                signal_running.store(false, Ordering::SeqCst);
+            }
+            true
+        }).map_err(|_| XSettingsResult::Failed)?;
+
+        let thread_running = running.clone();
+        thread::spawn(move || {
+            while thread_running.load(Ordering::SeqCst) {
+                let _ = watcher.process(Duration::from_millis(1000));
+            }
+        });
+
+        Ok((PortalClient { connection: reader, running: Some(running) }, receiver))
+    }
+
+    fn split_name(name: &[u8]) -> Result<(String, String), Error> {
+        let name = str::from_utf8(name).map_err(|_| XSettingsResult::Failed)?;
+        match name.rfind('/') {
+            Some(index) => Ok((name[..index].to_string(), name[index + 1..].to_string())),
+            None => Err(XSettingsResult::NoEntry),
+        }
+    }
+}
+
+impl Drop for PortalClient {
+    fn drop(&mut self) {
+        if let Some(ref running) = self.running {
+            running.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Backend for PortalClient {
+    fn get_setting(&self, name: &[u8]) -> Result<Setting, Error> {
+        let (namespace, key) = PortalClient::split_name(name)?;
+        let proxy = self.connection.with_proxy(PORTAL_BUS_NAME, PORTAL_OBJECT_PATH,
+                                               Duration::from_millis(PORTAL_TIMEOUT_MS));
+        let (value,): (Variant<Box<RefArg>>,) =
+            proxy.method_call(PORTAL_INTERFACE, "ReadOne", (namespace, key))
+                .map_err(|_| XSettingsResult::NoEntry)?;
+        Ok(Setting::from_owned(variant_to_data(&value)))
+    }
+
+    fn enumerate(&self) -> Result<Vec<Vec<u8>>, Error> {
+        // The portal is addressed by (namespace, key); without a namespace
+        // to query there is nothing to list, and unlike "no settings found"
+        // this is a hard limitation of the interface, not an empty result.
+        Err(XSettingsResult::Unsupported)
+    }
+}
+
+fn variant_to_data(variant: &Variant<Box<RefArg>>) -> OwnedSettingData {
+    if let Some(value) = variant.0.as_i64() {
+        return OwnedSettingData::Int(value as i32);
+    }
+    if let Some(value) = variant.0.as_str() {
+        return OwnedSettingData::String(value.as_bytes().to_vec());
+    }
+    if let Some(color) = struct_to_color(&*variant.0) {
+        return OwnedSettingData::Color(color);
+    }
+    OwnedSettingData::None
+}
+
+/// The portal represents colors as a `(ddd)` struct of red/green/blue
+/// components in the 0.0-1.0 range (see `org.freedesktop.portal.Settings`);
+/// XSETTINGS colors are CARD16 components, so each is scaled up and alpha
+/// is assumed opaque since the portal doesn't carry one.
+fn struct_to_color(value: &RefArg) -> Option<XSettingsColor> {
+    let components: Vec<f64> = match value.as_iter() {
+        Some(iter) => iter.filter_map(|component| component.as_f64()).collect(),
+        None => return None,
+    };
+    if components.len() != 3 {
+        return None;
+    }
+    let scale = |component: f64| (component.max(0.0).min(1.0) * 65535.0).round() as u16;
+    Some(XSettingsColor {
+        red: scale(components[0]),
+        green: scale(components[1]),
+        blue: scale(components[2]),
+        alpha: 0xffff,
+    })
+}
+
+struct SettingChanged {
+    namespace: String,
+    key: String,
+    value: Variant<Box<RefArg>>,
+}
+
+impl dbus::arg::ReadAll for SettingChanged {
+    fn read(i: &mut dbus::arg::Iter) -> Result<SettingChanged, dbus::arg::TypeMismatchError> {
+        Ok(SettingChanged {
+            namespace: i.read()?,
+            key: i.read()?,
+            value: i.read()?,
+        })
+    }
+}
+
+impl dbus::message::SignalArgs for SettingChanged {
+    const NAME: &'static str = "SettingChanged";
+    const INTERFACE: &'static str = PORTAL_INTERFACE;
+}
@@ -14,7 +14,7 @@ extern crate x11_dl;
 use std::ptr;
 use std::str;
 use x11_dl::xlib::Xlib;
-use xsettings::Client;
+use xsettings::X11Client;
 
 pub fn main() {
     let display;
@@ -24,7 +24,7 @@ pub fn main() {
         display = (xlib.XOpenDisplay)(ptr::null_mut());
 
         // Enumerate all properties.
-        client = Client::new(display,
+        client = X11Client::new(display,
                              (xlib.XDefaultScreen)(display),
                              Box::new(|name, _, setting| {
                                  println!("{:?}={:?}", str::from_utf8(name), setting)
@@ -0,0 +1,123 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A callback-free way to watch for setting changes: `X11Client::subscribe`
+//! turns the notify callback into a channel of `SettingChange`s. It is still
+//! an `unsafe fn`, since it takes a raw `Display` pointer.
+
+use libc::c_int;
+use std::mem;
+use std::sync::mpsc::{self, Receiver};
+use x11_dl::xlib::{Display, XWindowAttributes, Xlib};
+use {X11Client, OwnedSettingData, SettingData, SettingRef, XSettingsAction};
+
+/// One setting addition, change, or removal, as delivered by the channel
+/// returned from `X11Client::subscribe`.
+///
+/// `SettingRef` is only valid for the duration of the notify callback that
+/// produced it, so the value is deep-copied into this owned form before
+/// being sent.
+#[derive(Clone, Debug)]
+pub struct SettingChange {
+    pub name: Vec<u8>,
+    pub action: XSettingsAction,
+    pub value: Option<OwnedSettingData>,
+}
+
+impl X11Client {
+    /// Connects to the XSETTINGS manager for `screen` on `display` and
+    /// returns an `X11Client` together with a `Receiver` of `SettingChange`s,
+    /// without requiring the caller to build notify/watch callbacks
+    /// themselves.
+    ///
+    /// Still `unsafe`, like `X11Client::new`, because it dereferences the
+    /// raw `display` pointer; the ergonomic win over `new` is not having to
+    /// write the notify/watch callbacks, not avoiding `unsafe` altogether.
+    ///
+    /// Event processing still has to be driven by the caller: forward
+    /// `XEvent`s for `display` to `client.process_event`, and the resulting
+    /// changes will show up on the receiver.
+    pub unsafe fn subscribe(display: *mut Display, screen: c_int)
+                            -> (X11Client, Receiver<SettingChange>) {
+        let (sender, receiver) = mpsc::channel();
+
+        let notify = Box::new(move |name: &[u8], action: XSettingsAction, setting: SettingRef| {
+            let value = match action {
+                XSettingsAction::Deleted => None,
+                XSettingsAction::New | XSettingsAction::Changed => {
+                    Some(owned_data_of(setting.data()))
+                }
+            };
+            let _ = sender.send(SettingChange {
+                name: name.to_vec(),
+                action: action,
+                value: value,
+            });
+        });
+        // `X11Client::new` does not select events on root/the manager window
+        // itself; it asks `watch` to. `display` belongs to the caller, who
+        // may have selected events of their own on the same windows, so
+        // `watch` adds its bits to (and later removes them from) the mask
+        // already selected instead of replacing it.
+        let watch_xlib = Xlib::open().expect("xsettings: failed to open libX11");
+        let watch = Box::new(move |window, is_start, mask| {
+            let mut attributes: XWindowAttributes = mem::zeroed();
+            (watch_xlib.XGetWindowAttributes)(display, window, &mut attributes);
+            let current = attributes.your_event_mask;
+            let new_mask = if is_start { current | mask } else { current & !mask };
+            (watch_xlib.XSelectInput)(display, window, new_mask);
+        });
+
+        let client = X11Client::new(display, screen, notify, watch);
+        (client, receiver)
+    }
+}
+
+fn owned_data_of(data: SettingData) -> OwnedSettingData {
+    match data {
+        SettingData::Int(value) => OwnedSettingData::Int(value),
+        SettingData::String(bytes) => OwnedSettingData::String(bytes.to_vec()),
+        SettingData::Color(color) => OwnedSettingData::Color(color),
+        SettingData::None => OwnedSettingData::None,
+    }
+}
+
+/// A `futures::Stream` adapter over a `Receiver<SettingChange>`, enabled by
+/// the `futures` feature for callers that drive an event loop with futures
+/// rather than polling the `Receiver` themselves. With the X11 backend,
+/// `process_event` still has to be called for changes to arrive.
+#[cfg(feature = "futures")]
+pub struct SettingChangeStream {
+    receiver: Receiver<SettingChange>,
+}
+
+#[cfg(feature = "futures")]
+impl SettingChangeStream {
+    pub fn new(receiver: Receiver<SettingChange>) -> SettingChangeStream {
+        SettingChangeStream {
+            receiver: receiver,
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl ::futures::Stream for SettingChangeStream {
+    type Item = SettingChange;
+    type Error = ();
+
+    fn poll(&mut self) -> ::futures::Poll<Option<SettingChange>, ()> {
+        use futures::Async;
+        match self.receiver.try_recv() {
+            Ok(change) => Ok(Async::Ready(Some(change))),
+            Err(mpsc::TryRecvError::Empty) => Ok(Async::NotReady),
+            Err(mpsc::TryRecvError::Disconnected) => Ok(Async::Ready(None)),
+        }
+    }
+}
@@ -0,0 +1,371 @@
+// Copyright 2016 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Encoding and decoding of the `_XSETTINGS_SETTINGS` property blob.
+//!
+//! The wire format is described in the XSETTINGS specification: a header of
+//! a byte-order flag, three padding bytes, a CARD32 serial, and a CARD32
+//! setting count, followed by that many setting records. Every multi-byte
+//! field is encoded in the byte order the header declares, which need not
+//! match the byte order of the machine reading or writing it.
+
+use {OwnedSetting, OwnedSettingData, XSettingsColor, XSettingsResult, XSettingsType};
+
+const TYPE_INT: u8 = XSettingsType::Int as u8;
+const TYPE_STRING: u8 = XSettingsType::String as u8;
+const TYPE_COLOR: u8 = XSettingsType::Color as u8;
+
+const HEADER_LEN: usize = 12;
+
+#[derive(Copy, Clone)]
+pub enum ByteOrder {
+    Lsb,
+    Msb,
+}
+
+impl ByteOrder {
+    /// The byte order of the `_XSETTINGS_SETTINGS` property this process
+    /// will write; clients are required to accept either.
+    pub fn native() -> ByteOrder {
+        if cfg!(target_endian = "big") {
+            ByteOrder::Msb
+        } else {
+            ByteOrder::Lsb
+        }
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            ByteOrder::Lsb => 0,
+            ByteOrder::Msb => 1,
+        }
+    }
+
+    fn read_u16(self, bytes: &[u8]) -> u16 {
+        match self {
+            ByteOrder::Lsb => (bytes[0] as u16) | ((bytes[1] as u16) << 8),
+            ByteOrder::Msb => ((bytes[0] as u16) << 8) | (bytes[1] as u16),
+        }
+    }
+
+    fn read_u32(self, bytes: &[u8]) -> u32 {
+        match self {
+            ByteOrder::Lsb => {
+                (bytes[0] as u32) | ((bytes[1] as u32) << 8) | ((bytes[2] as u32) << 16) |
+                    ((bytes[3] as u32) << 24)
+            }
+            ByteOrder::Msb => {
+                ((bytes[0] as u32) << 24) | ((bytes[1] as u32) << 16) | ((bytes[2] as u32) << 8) |
+                    (bytes[3] as u32)
+            }
+        }
+    }
+
+    fn write_u16(self, value: u16, out: &mut Vec<u8>) {
+        match self {
+            ByteOrder::Lsb => {
+                out.push((value & 0xff) as u8);
+                out.push((value >> 8) as u8);
+            }
+            ByteOrder::Msb => {
+                out.push((value >> 8) as u8);
+                out.push((value & 0xff) as u8);
+            }
+        }
+    }
+
+    fn write_u32(self, value: u32, out: &mut Vec<u8>) {
+        match self {
+            ByteOrder::Lsb => {
+                out.push((value & 0xff) as u8);
+                out.push(((value >> 8) & 0xff) as u8);
+                out.push(((value >> 16) & 0xff) as u8);
+                out.push(((value >> 24) & 0xff) as u8);
+            }
+            ByteOrder::Msb => {
+                out.push(((value >> 24) & 0xff) as u8);
+                out.push(((value >> 16) & 0xff) as u8);
+                out.push(((value >> 8) & 0xff) as u8);
+                out.push((value & 0xff) as u8);
+            }
+        }
+    }
+}
+
+fn padded_len(len: usize) -> usize {
+    len + ((4 - (len % 4)) % 4)
+}
+
+/// Parses a `_XSETTINGS_SETTINGS` property blob, returning the serial it
+/// carried and the settings it describes.
+pub fn parse(bytes: &[u8]) -> Result<(u32, Vec<OwnedSetting>), XSettingsResult> {
+    if bytes.len() < HEADER_LEN {
+        return Err(XSettingsResult::Failed);
+    }
+    let order = match bytes[0] {
+        0 => ByteOrder::Lsb,
+        1 => ByteOrder::Msb,
+        _ => return Err(XSettingsResult::Failed),
+    };
+    let serial = order.read_u32(&bytes[4..8]);
+    let count = order.read_u32(&bytes[8..12]);
+
+    // `count` comes straight from the property and is not trustworthy (the
+    // selection owner is another client); cap the reservation at the number
+    // of bytes actually available rather than believing a claim of, say,
+    // 0xFFFFFFFF settings.
+    let mut settings = Vec::with_capacity((count as usize).min(bytes.len()));
+    let mut pos = HEADER_LEN;
+    for _ in 0..count {
+        let (setting, next) = parse_one(bytes, pos, order)?;
+        settings.push(setting);
+        pos = next;
+    }
+    Ok((serial, settings))
+}
+
+fn parse_one(bytes: &[u8], pos: usize, order: ByteOrder)
+             -> Result<(OwnedSetting, usize), XSettingsResult> {
+    if pos + 4 > bytes.len() {
+        return Err(XSettingsResult::Failed);
+    }
+    let setting_type = bytes[pos];
+    // bytes[pos + 1] is unused padding.
+    let name_len = order.read_u16(&bytes[pos + 2..pos + 4]) as usize;
+    let mut pos = pos + 4;
+
+    if pos + name_len > bytes.len() {
+        return Err(XSettingsResult::Failed);
+    }
+    let name = bytes[pos..pos + name_len].to_vec();
+    pos += padded_len(name_len);
+
+    if pos + 4 > bytes.len() {
+        return Err(XSettingsResult::Failed);
+    }
+    let last_change_serial = order.read_u32(&bytes[pos..pos + 4]);
+    pos += 4;
+
+    let data = match setting_type {
+        TYPE_INT => {
+            if pos + 4 > bytes.len() {
+                return Err(XSettingsResult::Failed);
+            }
+            let value = order.read_u32(&bytes[pos..pos + 4]) as i32;
+            pos += 4;
+            OwnedSettingData::Int(value)
+        }
+        TYPE_STRING => {
+            if pos + 4 > bytes.len() {
+                return Err(XSettingsResult::Failed);
+            }
+            let len = order.read_u32(&bytes[pos..pos + 4]) as usize;
+            pos += 4;
+            if pos + len > bytes.len() {
+                return Err(XSettingsResult::Failed);
+            }
+            let value = bytes[pos..pos + len].to_vec();
+            pos += padded_len(len);
+            OwnedSettingData::String(value)
+        }
+        TYPE_COLOR => {
+            if pos + 8 > bytes.len() {
+                return Err(XSettingsResult::Failed);
+            }
+            let red = order.read_u16(&bytes[pos..pos + 2]);
+            let green = order.read_u16(&bytes[pos + 2..pos + 4]);
+            let blue = order.read_u16(&bytes[pos + 4..pos + 6]);
+            let alpha = order.read_u16(&bytes[pos + 6..pos + 8]);
+            pos += 8;
+            OwnedSettingData::Color(XSettingsColor {
+                red: red,
+                green: green,
+                blue: blue,
+                alpha: alpha,
+            })
+        }
+        _ => return Err(XSettingsResult::Failed),
+    };
+
+    Ok((OwnedSetting {
+        name: name,
+        last_change_serial: last_change_serial,
+        data: data,
+    }, pos))
+}
+
+/// Serializes `settings` into a `_XSETTINGS_SETTINGS` property blob in the
+/// machine's native byte order, the inverse of `parse`.
+pub fn serialize(serial: u32, settings: &[OwnedSetting]) -> Vec<u8> {
+    // `OwnedSettingData::None` has no wire representation, so it must be
+    // excluded from both the emitted records and the header's count;
+    // writing a count that includes it would desynchronize `parse`'s
+    // record-by-record walk for everything that follows.
+    let settings: Vec<&OwnedSetting> = settings.iter()
+        .filter(|setting| setting.data != OwnedSettingData::None)
+        .collect();
+
+    let order = ByteOrder::native();
+    let mut out = Vec::new();
+    out.push(order.tag());
+    out.extend_from_slice(&[0, 0, 0]);
+    order.write_u32(serial, &mut out);
+    order.write_u32(settings.len() as u32, &mut out);
+
+    for setting in settings {
+        let setting_type = match setting.data {
+            OwnedSettingData::Int(_) => TYPE_INT,
+            OwnedSettingData::String(_) => TYPE_STRING,
+            OwnedSettingData::Color(_) => TYPE_COLOR,
+            OwnedSettingData::None => unreachable!(),
+        };
+        out.push(setting_type);
+        out.push(0);
+        order.write_u16(setting.name.len() as u16, &mut out);
+        out.extend_from_slice(&setting.name);
+        out.resize(out.len() + (padded_len(setting.name.len()) - setting.name.len()), 0);
+        order.write_u32(setting.last_change_serial, &mut out);
+
+        match setting.data {
+            OwnedSettingData::Int(value) => order.write_u32(value as u32, &mut out),
+            OwnedSettingData::String(ref value) => {
+                order.write_u32(value.len() as u32, &mut out);
+                out.extend_from_slice(value);
+                out.resize(out.len() + (padded_len(value.len()) - value.len()), 0);
+            }
+            OwnedSettingData::Color(color) => {
+                order.write_u16(color.red, &mut out);
+                order.write_u16(color.green, &mut out);
+                order.write_u16(color.blue, &mut out);
+                order.write_u16(color.alpha, &mut out);
+            }
+            OwnedSettingData::None => {}
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, serialize, ByteOrder};
+    use {OwnedSetting, OwnedSettingData, XSettingsColor, XSettingsResult};
+
+    fn settings() -> Vec<OwnedSetting> {
+        vec![
+            OwnedSetting {
+                name: b"Net/ThemeName".to_vec(),
+                last_change_serial: 1,
+                data: OwnedSettingData::String(b"Adwaita".to_vec()),
+            },
+            OwnedSetting {
+                name: b"Gdk/WindowScalingFactor".to_vec(),
+                last_change_serial: 2,
+                data: OwnedSettingData::Int(2),
+            },
+            OwnedSetting {
+                name: b"Net/CursorColor".to_vec(),
+                last_change_serial: 3,
+                data: OwnedSettingData::Color(XSettingsColor {
+                    red: 0x1111,
+                    green: 0x2222,
+                    blue: 0x3333,
+                    alpha: 0xffff,
+                }),
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = serialize(42, &settings());
+        let (serial, parsed) = parse(&bytes).unwrap();
+        assert_eq!(serial, 42);
+        assert_eq!(parsed, settings());
+    }
+
+    #[test]
+    fn round_trip_empty() {
+        let bytes = serialize(0, &[]);
+        let (serial, parsed) = parse(&bytes).unwrap();
+        assert_eq!(serial, 0);
+        assert!(parsed.is_empty());
+    }
+
+    #[test]
+    fn parse_rejects_buffer_shorter_than_header() {
+        assert_eq!(parse(&[0, 0, 0]), Err(XSettingsResult::Failed));
+    }
+
+    #[test]
+    fn parse_rejects_bad_byte_order_tag() {
+        let mut bytes = serialize(1, &settings());
+        bytes[0] = 0xff;
+        assert_eq!(parse(&bytes), Err(XSettingsResult::Failed));
+    }
+
+    #[test]
+    fn parse_rejects_truncated_setting() {
+        let bytes = serialize(1, &settings());
+        // Cut the blob off partway through the last setting's value: the
+        // header's count still claims three settings, but there isn't room
+        // for them, which is exactly the "untrusted count" shape that
+        // motivated capping the `Vec::with_capacity` reservation in `parse`.
+        let truncated = &bytes[..bytes.len() - 4];
+        assert_eq!(parse(truncated), Err(XSettingsResult::Failed));
+    }
+
+    #[test]
+    fn parse_rejects_truncated_before_any_setting() {
+        // Twelve-byte header claiming one setting, with nothing after it.
+        let bytes = vec![ByteOrder::native().tag(), 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0];
+        assert_eq!(parse(&bytes), Err(XSettingsResult::Failed));
+    }
+
+    #[test]
+    fn msb_and_lsb_round_trip_identically() {
+        for &order in &[ByteOrder::Lsb, ByteOrder::Msb] {
+            let mut out = Vec::new();
+            out.push(order.tag());
+            out.extend_from_slice(&[0, 0, 0]);
+            order.write_u32(7, &mut out);
+            order.write_u32(0, &mut out);
+            let (serial, parsed) = parse(&out).unwrap();
+            assert_eq!(serial, 7);
+            assert!(parsed.is_empty());
+        }
+    }
+
+    #[test]
+    fn serialize_excludes_none_settings_from_count_and_body() {
+        let mut with_none = settings();
+        with_none.push(OwnedSetting {
+            name: b"Net/Unset".to_vec(),
+            last_change_serial: 4,
+            data: OwnedSettingData::None,
+        });
+
+        let bytes = serialize(42, &with_none);
+        let (serial, parsed) = parse(&bytes).unwrap();
+        assert_eq!(serial, 42);
+        assert_eq!(parsed, settings());
+    }
+
+    #[test]
+    fn msb_and_lsb_disagree_on_multi_byte_values() {
+        let mut lsb = Vec::new();
+        ByteOrder::Lsb.write_u32(0x01020304, &mut lsb);
+        let mut msb = Vec::new();
+        ByteOrder::Msb.write_u32(0x01020304, &mut msb);
+        assert_ne!(lsb, msb);
+        assert_eq!(ByteOrder::Lsb.read_u32(&lsb), 0x01020304);
+        assert_eq!(ByteOrder::Msb.read_u32(&msb), 0x01020304);
+    }
+}